@@ -0,0 +1,115 @@
+//! Content-addressed identity for `Protocol` messages.
+//!
+//! Before a message enters the `NetConnectionThread`, `ContentId::of`
+//! computes a real CIDv1 over its canonical serialized bytes: a multihash
+//! (the hash algorithm code is encoded in the identifier itself, so the
+//! default sha2-256 can later be swapped for blake2b or anything else
+//! without changing callers) plus the CIDv1 version and content-type codec,
+//! multibase-encoded so the textual form alone is enough to decode it.
+//! `P2pNetwork` uses this as the message's network identity for gossip dedup,
+//! idempotent sends, and the id handed to subscribers; the workers themselves
+//! never compute it.
+
+use cid::Cid;
+use holochain_net_connection::{protocol::Protocol, NetResult};
+use multihash::{Code, MultihashDigest};
+use std::{
+    collections::{HashSet, VecDeque},
+    fmt,
+    sync::Mutex,
+};
+
+/// The serialized bytes aren't themselves decoded as any particular
+/// multicodec-described format by anything reading the CID, so they're
+/// tagged with the generic "raw binary" multicodec rather than claiming a
+/// more specific content type.
+const RAW_BINARY_CODEC: u64 = 0x55;
+
+/// A CIDv1 identifying a `Protocol` message: version + codec + an
+/// algorithm-agile multihash, multibase-encoded so the textual form alone
+/// is enough to decode it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ContentId(String);
+
+impl ContentId {
+    /// Compute the CID of `protocol`'s canonical serialized bytes using the
+    /// default sha2-256 multihash.
+    pub fn of(protocol: &Protocol) -> NetResult<Self> {
+        Self::of_with_code(protocol, Code::Sha2_256)
+    }
+
+    /// Compute the CID of `protocol`'s canonical serialized bytes, using
+    /// `code` as the multihash algorithm. The algorithm code travels with
+    /// the resulting identifier, so future messages can mix hash
+    /// algorithms without breaking decoding.
+    pub fn of_with_code(protocol: &Protocol, code: Code) -> NetResult<Self> {
+        let bytes: Vec<u8> = protocol.clone().into();
+        let hash = code.digest(&bytes);
+        let cid = Cid::new_v1(RAW_BINARY_CODEC, hash);
+        Ok(ContentId(cid.to_string()))
+    }
+}
+
+impl fmt::Display for ContentId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Bounded cache of recently-seen `ContentId`s, used to drop duplicate
+/// gossip arriving over multiple paths before it reaches the `NetHandler`.
+///
+/// Eviction is FIFO by insertion order, not LRU: a hit doesn't refresh an
+/// id's position, so a ContentId that keeps reappearing is evicted on the
+/// same schedule as one seen only once. `seen` gives O(1) membership checks
+/// instead of scanning `order` linearly on every message; `order` exists
+/// only to know which id to evict next.
+pub struct SeenIdCache {
+    capacity: usize,
+    inner: Mutex<SeenIdCacheInner>,
+}
+
+struct SeenIdCacheInner {
+    seen: HashSet<ContentId>,
+    order: VecDeque<ContentId>,
+}
+
+impl SeenIdCache {
+    /// Create a cache retaining at most `capacity` of the most recently
+    /// inserted ids.
+    pub fn new(capacity: usize) -> Self {
+        SeenIdCache {
+            capacity,
+            inner: Mutex::new(SeenIdCacheInner {
+                seen: HashSet::with_capacity(capacity),
+                order: VecDeque::with_capacity(capacity),
+            }),
+        }
+    }
+
+    /// Record `id` as seen. Returns `true` if this is the first time `id`
+    /// has been observed (i.e. the caller should process the message),
+    /// `false` if it's a duplicate that should be dropped.
+    pub fn insert(&self, id: ContentId) -> bool {
+        let mut inner = self.inner.lock().expect("seen-id cache lock poisoned");
+        if inner.seen.contains(&id) {
+            return false;
+        }
+        if inner.order.len() >= self.capacity {
+            if let Some(oldest) = inner.order.pop_front() {
+                inner.seen.remove(&oldest);
+            }
+        }
+        inner.order.push_back(id.clone());
+        inner.seen.insert(id);
+        true
+    }
+}
+
+impl Default for SeenIdCache {
+    /// Retains the 4096 most recently inserted ids, enough to cover typical
+    /// gossip fan-out without unbounded growth.
+    fn default() -> Self {
+        SeenIdCache::new(4096)
+    }
+}
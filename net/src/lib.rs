@@ -0,0 +1,15 @@
+//! Crate root: wires up the p2p backend modules and re-exports the public
+//! facade (`P2pNetwork`) and its configuration types.
+
+mod content_id;
+mod ipc_net_worker;
+mod lib3h_codec;
+mod lib3h_worker;
+mod mock_worker;
+mod p2p_config;
+mod p2p_network;
+mod tick_policy;
+mod unix_ipc_worker;
+
+pub use p2p_config::*;
+pub use p2p_network::P2pNetwork;
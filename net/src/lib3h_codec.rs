@@ -0,0 +1,110 @@
+//! Wire codec for the `Lib3hWorker` direct-messaging behaviour.
+//!
+//! Gossipsub already frames DHT traffic for us; direct (1:1) messages use
+//! libp2p's `request_response` behaviour instead, which needs a small codec
+//! telling it how to read/write a `Protocol` off the wire.
+
+use holochain_net_connection::protocol::Protocol;
+use libp2p::core::ProtocolName;
+use libp2p::request_response::RequestResponseCodec;
+use std::io;
+
+/// The single protocol this codec speaks, advertised during libp2p
+/// multistream-select negotiation.
+#[derive(Debug, Clone)]
+pub struct DirectMessageProtocol;
+
+impl ProtocolName for DirectMessageProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        b"/holochain/direct-message/1.0.0"
+    }
+}
+
+/// Length-prefixed bincode framing for `Protocol` request/response pairs.
+#[derive(Debug, Clone)]
+pub struct DirectMessageCodec;
+
+/// A direct message in flight; unwraps back to the `Protocol` it carries.
+pub struct DirectMessage(pub Protocol);
+
+impl DirectMessage {
+    pub fn try_into_protocol(self) -> Result<Protocol, io::Error> {
+        Ok(self.0)
+    }
+}
+
+#[async_trait::async_trait]
+impl RequestResponseCodec for DirectMessageCodec {
+    type Protocol = DirectMessageProtocol;
+    type Request = DirectMessage;
+    type Response = DirectMessage;
+
+    async fn read_request<T>(
+        &mut self,
+        _: &DirectMessageProtocol,
+        io: &mut T,
+    ) -> io::Result<Self::Request>
+    where
+        T: futures::AsyncRead + Unpin + Send,
+    {
+        read_framed_protocol(io).await.map(DirectMessage)
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        _: &DirectMessageProtocol,
+        io: &mut T,
+    ) -> io::Result<Self::Response>
+    where
+        T: futures::AsyncRead + Unpin + Send,
+    {
+        read_framed_protocol(io).await.map(DirectMessage)
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &DirectMessageProtocol,
+        io: &mut T,
+        DirectMessage(data): Self::Request,
+    ) -> io::Result<()>
+    where
+        T: futures::AsyncWrite + Unpin + Send,
+    {
+        write_framed_protocol(io, data).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &DirectMessageProtocol,
+        io: &mut T,
+        DirectMessage(data): Self::Response,
+    ) -> io::Result<()>
+    where
+        T: futures::AsyncWrite + Unpin + Send,
+    {
+        write_framed_protocol(io, data).await
+    }
+}
+
+async fn read_framed_protocol<T>(io: &mut T) -> io::Result<Protocol>
+where
+    T: futures::AsyncRead + Unpin + Send,
+{
+    use futures::AsyncReadExt;
+    let mut len_buf = [0u8; 4];
+    io.read_exact(&mut len_buf).await?;
+    let len = u32::from_be_bytes(len_buf) as usize;
+    let mut buf = vec![0u8; len];
+    io.read_exact(&mut buf).await?;
+    Protocol::try_from(buf.as_slice()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+async fn write_framed_protocol<T>(io: &mut T, data: Protocol) -> io::Result<()>
+where
+    T: futures::AsyncWrite + Unpin + Send,
+{
+    use futures::AsyncWriteExt;
+    let bytes: Vec<u8> = data.into();
+    io.write_all(&(bytes.len() as u32).to_be_bytes()).await?;
+    io.write_all(&bytes).await
+}
@@ -0,0 +1,285 @@
+//! Native in-process p2p backend built on libp2p.
+//!
+//! Unlike `IpcNetWorker`, which talks to an out-of-process n3h node over
+//! ZMQ, `Lib3hWorker` owns its networking stack directly: a libp2p `Swarm`
+//! running a gossipsub behaviour (DHT publish/fetch) composed with a
+//! request/response behaviour (direct messaging). The worker's `tick()`
+//! drives the swarm's event loop from inside `NetConnectionThread`,
+//! translating `SwarmEvent`s into `Protocol` messages for the `NetHandler`
+//! and mapping every outbound `Protocol` send onto a gossipsub publish.
+//! Direct messages are only received: no outbound send dials a peer or
+//! issues a request/response request (peers are dialed only for the
+//! configured bootstrap addresses in `new()`).
+
+use holochain_net_connection::{
+    net_connection::{NetHandler, NetWorker},
+    protocol::Protocol,
+    NetResult,
+};
+
+use libp2p::{
+    core::transport::{Transport, TransportError},
+    gossipsub::{error::PublishError, Gossipsub, GossipsubConfigBuilder, GossipsubEvent, Topic},
+    identity,
+    noise,
+    request_response::{RequestResponse, RequestResponseEvent},
+    swarm::{Swarm, SwarmEvent},
+    tcp::TokioTcpConfig,
+    Multiaddr, NetworkBehaviour, PeerId,
+};
+
+use crate::tick_policy::{BackendNotReady, StartupRetry, TickPolicy, TickPolicyConfig};
+use failure::format_err;
+use futures::StreamExt;
+use std::{
+    collections::VecDeque,
+    io::ErrorKind,
+    task::{Context, Poll},
+};
+
+/// Well-known gossipsub topic carrying DHT publish/fetch traffic.
+const DHT_TOPIC: &str = "holochain-dht";
+
+/// Classify a `Swarm::listen_on` failure as transient (the address isn't
+/// bindable yet, e.g. still in use by a process that's shutting down) vs.
+/// a real configuration error the caller should see immediately.
+fn classify_listen_error(
+    e: TransportError<std::io::Error>,
+) -> BackendNotReady<TransportError<std::io::Error>> {
+    match &e {
+        TransportError::Other(io_err)
+            if io_err.kind() == ErrorKind::AddrInUse || io_err.kind() == ErrorKind::AddrNotAvailable =>
+        {
+            BackendNotReady::NotReady
+        }
+        _ => BackendNotReady::Other(e),
+    }
+}
+
+/// Combines gossipsub (DHT gossip) and request/response (direct messaging)
+/// into a single libp2p `NetworkBehaviour` driven by one `Swarm`.
+#[derive(NetworkBehaviour)]
+struct Lib3hBehaviour {
+    gossipsub: Gossipsub,
+    direct_message: RequestResponse<crate::lib3h_codec::DirectMessageCodec>,
+}
+
+/// JSON shape of `backend_config` for `P2pBackendKind::Lib3h`.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Lib3hConfig {
+    /// Multiaddrs this node should listen on, e.g. `/ip4/0.0.0.0/tcp/0`.
+    pub listen_addrs: Vec<String>,
+    /// Bootstrap peers to dial on startup, as `Multiaddr` strings.
+    #[serde(default)]
+    pub bootstrap_peers: Vec<String>,
+    /// Idle/backoff sleep thresholds and startup-retry interval.
+    #[serde(flatten, default)]
+    pub tick_policy: TickPolicyConfig,
+}
+
+/// In-process libp2p-backed `NetWorker`.
+pub struct Lib3hWorker {
+    handler: NetHandler,
+    swarm: Swarm<Lib3hBehaviour>,
+    local_peer_id: PeerId,
+    /// Inbound `Protocol`s decoded from swarm events, drained on `tick()`.
+    inbox: VecDeque<Protocol>,
+    endpoint: String,
+    tick_policy: TickPolicy,
+    /// `TokioTcpConfig`'s transport is tokio I/O under the hood and panics
+    /// without a reactor running. This keeps one alive for the worker's
+    /// whole lifetime; `new()` and every swarm poll run with it entered so
+    /// the transport always sees an active runtime context.
+    runtime: tokio::runtime::Runtime,
+}
+
+impl Lib3hWorker {
+    /// Create a new worker from its JSON `backend_config`, bringing up a
+    /// TCP+noise+gossipsub swarm and dialing any configured bootstrap peers.
+    pub fn new(handler: NetHandler, backend_config: &serde_json::Value) -> NetResult<Self> {
+        let config: Lib3hConfig = serde_json::from_value(backend_config.clone())?;
+
+        let runtime = tokio::runtime::Runtime::new()?;
+        let _guard = runtime.enter();
+
+        let local_key = identity::Keypair::generate_ed25519();
+        let local_peer_id = PeerId::from(local_key.public());
+
+        let noise_keys = noise::Keypair::<noise::X25519Spec>::new().into_authentic(&local_key)?;
+        let transport = TokioTcpConfig::new()
+            .upgrade(libp2p::core::upgrade::Version::V1)
+            .authenticate(noise::NoiseConfig::xx(noise_keys).into_authenticated())
+            .multiplex(libp2p::yamux::YamuxConfig::default())
+            .boxed();
+
+        let gossipsub_config = GossipsubConfigBuilder::default().build()?;
+        let mut gossipsub = Gossipsub::new(
+            libp2p::gossipsub::MessageAuthenticity::Signed(local_key.clone()),
+            gossipsub_config,
+        )?;
+        gossipsub.subscribe(&Topic::new(DHT_TOPIC.to_string()))?;
+
+        let direct_message = RequestResponse::new(
+            crate::lib3h_codec::DirectMessageCodec,
+            std::iter::once((
+                crate::lib3h_codec::DirectMessageProtocol,
+                libp2p::request_response::ProtocolSupport::Full,
+            )),
+            Default::default(),
+        );
+
+        let behaviour = Lib3hBehaviour {
+            gossipsub,
+            direct_message,
+        };
+        let mut swarm = Swarm::new(transport, behaviour, local_peer_id.clone());
+
+        // Listening can legitimately fail transiently while the local
+        // network stack is still coming up (e.g. right after a container
+        // starts); retry on a timer instead of failing `new()` outright.
+        let startup_retry = StartupRetry::new(&config.tick_policy);
+        let mut endpoint = String::new();
+        for addr in &config.listen_addrs {
+            let multiaddr: Multiaddr = addr.parse()?;
+            startup_retry.retry_until_ready(|| {
+                Swarm::listen_on(&mut swarm, multiaddr.clone()).map_err(classify_listen_error)
+            })?;
+            if endpoint.is_empty() {
+                endpoint = addr.clone();
+            }
+        }
+        for peer in &config.bootstrap_peers {
+            let multiaddr: Multiaddr = peer.parse()?;
+            let _ = Swarm::dial_addr(&mut swarm, multiaddr);
+        }
+
+        Ok(Lib3hWorker {
+            handler,
+            swarm,
+            local_peer_id,
+            inbox: VecDeque::new(),
+            endpoint,
+            tick_policy: TickPolicy::new(&config.tick_policy),
+            runtime,
+        })
+    }
+
+    /// Translate one libp2p `SwarmEvent` into zero or more `Protocol`
+    /// messages queued for delivery to the `NetHandler`.
+    fn handle_swarm_event(&mut self, event: SwarmEvent<Lib3hBehaviourEvent, impl std::error::Error>) {
+        if let SwarmEvent::Behaviour(event) = event {
+            match event {
+                Lib3hBehaviourEvent::Gossipsub(GossipsubEvent::Message { message, .. }) => {
+                    if let Ok(protocol) = Protocol::try_from(message.data.as_slice()) {
+                        self.inbox.push_back(protocol);
+                    }
+                }
+                Lib3hBehaviourEvent::DirectMessage(RequestResponseEvent::Message {
+                    message,
+                    ..
+                }) => {
+                    if let Ok(protocol) = message.try_into_protocol() {
+                        self.inbox.push_back(protocol);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Publish `data` to the DHT gossip topic. No peer being subscribed yet
+    /// (the common case right after startup, or during a temporary
+    /// partition) is not an error -- gossipsub simply has nobody to gossip
+    /// to right now, and a later publish will carry current state once
+    /// peers show up, so that case is swallowed rather than surfaced.
+    fn publish(&mut self, data: Protocol) -> NetResult<()> {
+        let bytes: Vec<u8> = data.into();
+        match self
+            .swarm
+            .gossipsub
+            .publish(&Topic::new(DHT_TOPIC.to_string()), bytes)
+        {
+            Ok(_message_id) => Ok(()),
+            Err(PublishError::InsufficientPeers) => Ok(()),
+            Err(e) => Err(format_err!("gossipsub publish failed: {:?}", e)),
+        }
+    }
+
+    /// Poll the swarm's `Stream` for one event without blocking. A `Swarm`
+    /// is driven by polling, not a sync "wait with timeout" call, so this
+    /// uses a no-op waker to ask "is anything ready right now?" on each tick
+    /// rather than parking the tick thread on the async runtime.
+    ///
+    /// The transport underneath is tokio I/O, which needs an entered
+    /// runtime context to register and check readiness at all -- without
+    /// this, the first real I/O touches a reactor that was never running.
+    fn poll_swarm_once(&mut self) -> Option<SwarmEvent<Lib3hBehaviourEvent, impl std::error::Error>> {
+        let _guard = self.runtime.enter();
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        match self.swarm.poll_next_unpin(&mut cx) {
+            Poll::Ready(Some(event)) => Some(event),
+            Poll::Ready(None) | Poll::Pending => None,
+        }
+    }
+}
+
+impl NetWorker for Lib3hWorker {
+    /// Drive the swarm's event loop for one tick, forwarding any messages
+    /// translated from inbound `SwarmEvent`s to the `NetHandler`.
+    fn tick(&mut self) -> NetResult<bool> {
+        let mut did_something = false;
+        while let Some(event) = self.poll_swarm_once() {
+            did_something = true;
+            self.handle_swarm_event(event);
+        }
+        while let Some(protocol) = self.inbox.pop_front() {
+            did_something = true;
+            (self.handler)(Ok(protocol))?;
+        }
+        // Sleep a minimal interval right after doing work, growing
+        // exponentially up to a ceiling while idle, so a quiet node isn't
+        // busy-spinning this tick loop.
+        std::thread::sleep(self.tick_policy.next_sleep(did_something));
+        Ok(did_something)
+    }
+
+    /// Map an outbound `Protocol` onto a gossipsub publish.
+    fn receive(&mut self, data: Protocol) -> NetResult<()> {
+        self.publish(data)
+    }
+
+    fn stop(self: Box<Self>) -> NetResult<()> {
+        Ok(())
+    }
+
+    fn endpoint(&self) -> Option<String> {
+        Some(format!("{}/p2p/{}", self.endpoint, self.local_peer_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config() -> serde_json::Value {
+        serde_json::json!({ "listen_addrs": ["/ip4/127.0.0.1/tcp/0"] })
+    }
+
+    #[test]
+    fn it_should_create_and_tick() {
+        let mut worker = Lib3hWorker::new(Box::new(|_r| Ok(())), &test_config()).unwrap();
+        // A freshly-created worker has no inbound events queued, so one
+        // tick should report no work and not block forever.
+        assert_eq!(worker.tick().unwrap(), false);
+        assert!(worker.endpoint().is_some());
+    }
+
+    #[test]
+    fn it_should_publish_outbound_sends_as_gossip() {
+        let mut worker = Lib3hWorker::new(Box::new(|_r| Ok(())), &test_config()).unwrap();
+        // No peers are subscribed yet, but publish()/receive() should still
+        // succeed rather than erroring out with no one listening.
+        worker.receive(Protocol::P2pReady).unwrap();
+    }
+}
@@ -9,12 +9,80 @@ use holochain_net_connection::{
     NetResult,
 };
 
-use super::{ipc_net_worker::IpcNetWorker, mock_worker::MockWorker, p2p_config::*};
+use super::{
+    content_id::{ContentId, SeenIdCache},
+    ipc_net_worker::IpcNetWorker,
+    lib3h_worker::Lib3hWorker,
+    mock_worker::MockWorker,
+    p2p_config::*,
+    unix_ipc_worker::UnixIpcWorker,
+};
+
+use std::{
+    os::unix::io::RawFd,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use failure::format_err;
+
+/// An inbound `Protocol` paired with its content id, so subscribers can
+/// reference or dedup on it without re-hashing the message themselves.
+#[derive(Debug, Clone)]
+pub struct IdentifiedProtocol {
+    pub protocol: Protocol,
+    pub id: ContentId,
+}
 
 /// Facade handling a network connection
 /// Holds a NetConnectionThread and implements itself the NetConnection Trait
 pub struct P2pNetwork {
-    connection: NetConnectionThread,
+    connection: Arc<Mutex<NetConnectionThread>>,
+    /// Tracer used to re-enter the caller's dispatcher, if any, so spans
+    /// created on `send()` are recorded under the right subscriber even
+    /// when the worker runs on another thread. This is the only tracing
+    /// correlation this type offers: per-message span propagation across
+    /// the connection-thread boundary would need the span to travel
+    /// alongside each `Protocol` through the channel, which nothing here
+    /// does, so it isn't claimed.
+    tracer: Option<tracing::Dispatch>,
+    /// Live `subscribe()`rs, each fanned out a clone of every inbound
+    /// `IdentifiedProtocol` in addition to the legacy `NetHandler`.
+    subscribers: Arc<Mutex<Vec<Sender<IdentifiedProtocol>>>>,
+    /// Clones of this handle are returned by `new()` so that multiple
+    /// internal subsystems (DHT, direct messaging, sync, ...) can drive
+    /// this same connection without all needing a `&mut P2pNetwork`.
+    outbound_sender: Sender<Protocol>,
+    /// Signals `spawn_outbound_forwarder`'s background thread to exit,
+    /// independent of whether `outbound_sender` clones are still alive
+    /// elsewhere -- `stop()` needs the thread to drop its `Arc<Mutex<..>>`
+    /// clone of `connection` even while other subsystems keep sending.
+    forwarder_shutdown: Sender<()>,
+    /// Joined by `stop()` after signalling `forwarder_shutdown`, so the
+    /// forwarder thread's `Arc` clone of `connection` is guaranteed gone
+    /// before `stop()` tries to unwrap it.
+    forwarder_handle: thread::JoinHandle<()>,
+    /// Whether content-id dedup is applied to sends/inbound messages at
+    /// all. Off by default -- most `Protocol` traffic (control frames,
+    /// direct messages, repeated fetch/publish) is meant to be resendable,
+    /// and only gossip fan-out benefits from dropping duplicates outright.
+    /// Enable with `new_with_gossip_dedup` for a handle dedicated to gossip
+    /// sends, rather than applying it blanket to every `Protocol` type.
+    dedup_gossip: Arc<Mutex<bool>>,
+    /// Ids of messages we've already sent, so `send()` is idempotent by
+    /// content when `dedup_gossip` is enabled.
+    sent_ids: Arc<SeenIdCache>,
+}
+
+/// Fan `message` out to every subscriber, dropping any whose receiver has
+/// been disconnected.
+fn fan_out_to_subscribers(
+    subscribers: &Arc<Mutex<Vec<Sender<IdentifiedProtocol>>>>,
+    message: &IdentifiedProtocol,
+) {
+    let mut subscribers = subscribers.lock().expect("subscribers lock poisoned");
+    subscribers.retain(|sender| sender.send(message.clone()).is_ok());
 }
 
 impl P2pNetwork {
@@ -22,7 +90,18 @@ impl P2pNetwork {
     /// `config` is the configuration of the p2p connection
     /// `handler` is the closure for handling received Protocol messages
     /// `send()` is used for sending Protocol messages to the network
-    pub fn new(handler: NetHandler, config: &P2pConfig) -> NetResult<Self> {
+    ///
+    /// Besides `self`, also returns a `Sender<Protocol>` clone that lets
+    /// other subsystems push outbound messages through this connection
+    /// without needing a `&mut P2pNetwork` of their own. Use `subscribe()`
+    /// to add additional consumers of inbound messages alongside `handler`.
+    pub fn new(handler: NetHandler, config: &P2pConfig) -> NetResult<(Self, Sender<Protocol>)> {
+        let subscribers: Arc<Mutex<Vec<Sender<IdentifiedProtocol>>>> =
+            Arc::new(Mutex::new(Vec::new()));
+        let inbound_seen = Arc::new(SeenIdCache::default());
+        let dedup_gossip = Arc::new(Mutex::new(false));
+        let handler = Self::wrap_handler(handler, subscribers.clone(), inbound_seen, dedup_gossip.clone());
+        let sent_ids = Arc::new(SeenIdCache::default());
         // Create Config struct
         let network_config = config.backend_config.to_string().into();
         // Provide worker factory dependening on backend kind
@@ -39,21 +118,227 @@ impl P2pNetwork {
                     Ok(Box::new(MockWorker::new(h, &network_config)?) as Box<NetWorker>)
                 })
             },
+            // Creates a Lib3hWorker, an in-process libp2p swarm, removing the
+            // need for an out-of-process n3h node entirely
+            P2pBackendKind::Lib3h => {
+                Box::new(move |h| {
+                    Ok(Box::new(Lib3hWorker::new(h, &network_config)?) as Box<NetWorker>)
+                })
+            },
+            // Creates a UnixIpcWorker, a length-prefixed bincode connection
+            // over a unix domain socket, with support for fd passing
+            P2pBackendKind::UnixIpc => {
+                Box::new(move |h| {
+                    Ok(Box::new(UnixIpcWorker::new(h, &network_config)?) as Box<NetWorker>)
+                })
+            },
         };
         // Create NetConnectionThread with appropriate worker factory
-        let connection = NetConnectionThread::new(handler, worker_factory, None)?;
+        let connection = Arc::new(Mutex::new(NetConnectionThread::new(
+            handler,
+            worker_factory,
+            None,
+        )?));
+        let (outbound_sender, forwarder_shutdown, forwarder_handle) =
+            Self::spawn_outbound_forwarder(connection.clone());
         // Done
-        Ok(P2pNetwork { connection })
+        Ok((
+            P2pNetwork {
+                connection,
+                tracer: None,
+                subscribers,
+                outbound_sender: outbound_sender.clone(),
+                forwarder_shutdown,
+                forwarder_handle,
+                dedup_gossip,
+                sent_ids,
+            },
+            outbound_sender,
+        ))
+    }
+
+    /// Like `new`, but makes `send()` run with `tracer` as the default
+    /// dispatcher on the calling thread for the duration of each call. No
+    /// span context is carried with the message to the connection thread,
+    /// and sends through the returned `Sender<Protocol>` don't use `tracer`.
+    pub fn new_with_tracer(
+        handler: NetHandler,
+        config: &P2pConfig,
+        tracer: tracing::Dispatch,
+    ) -> NetResult<(Self, Sender<Protocol>)> {
+        let (mut network, sender) = Self::new(handler, config)?;
+        network.tracer = Some(tracer);
+        Ok((network, sender))
+    }
+
+    /// Like `new`, but enables content-id dedup on both `send()` and
+    /// inbound messages. Intended for a handle dedicated to gossip traffic,
+    /// where resending an identical message is expected and safe to drop --
+    /// not for a handle that also carries control frames or direct
+    /// messages, where a legitimate resend would be silently lost.
+    pub fn new_with_gossip_dedup(
+        handler: NetHandler,
+        config: &P2pConfig,
+    ) -> NetResult<(Self, Sender<Protocol>)> {
+        let (network, sender) = Self::new(handler, config)?;
+        *network
+            .dedup_gossip
+            .lock()
+            .expect("dedup-gossip lock poisoned") = true;
+        Ok((network, sender))
+    }
+
+    /// Create a new p2p network connection over a unix domain socket that
+    /// was already opened by the parent process (systemd-style socket
+    /// activation) rather than one this crate creates or connects itself.
+    pub fn from_raw_fd(handler: NetHandler, fd: RawFd) -> NetResult<(Self, Sender<Protocol>)> {
+        let subscribers: Arc<Mutex<Vec<Sender<IdentifiedProtocol>>>> =
+            Arc::new(Mutex::new(Vec::new()));
+        let inbound_seen = Arc::new(SeenIdCache::default());
+        let dedup_gossip = Arc::new(Mutex::new(false));
+        let handler = Self::wrap_handler(handler, subscribers.clone(), inbound_seen, dedup_gossip.clone());
+        let worker_factory: NetWorkerFactory =
+            Box::new(move |h| Ok(Box::new(UnixIpcWorker::from_raw_fd(h, fd)?) as Box<NetWorker>));
+        let connection = Arc::new(Mutex::new(NetConnectionThread::new(
+            handler,
+            worker_factory,
+            None,
+        )?));
+        let (outbound_sender, forwarder_shutdown, forwarder_handle) =
+            Self::spawn_outbound_forwarder(connection.clone());
+        Ok((
+            P2pNetwork {
+                connection,
+                tracer: None,
+                subscribers,
+                outbound_sender: outbound_sender.clone(),
+                forwarder_shutdown,
+                forwarder_handle,
+                dedup_gossip,
+                sent_ids: Arc::new(SeenIdCache::default()),
+            },
+            outbound_sender,
+        ))
+    }
+
+    /// Getter of a `Sender<Protocol>` clone driving this same connection,
+    /// equivalent to the one returned alongside `self` by `new()`.
+    pub fn sender(&self) -> Sender<Protocol> {
+        self.outbound_sender.clone()
+    }
+
+    /// Add a new subscriber that receives a clone of every inbound
+    /// `Protocol` paired with its `ContentId`, independent of (and in
+    /// addition to) the legacy `NetHandler` passed to the constructor.
+    pub fn subscribe(&self) -> Receiver<IdentifiedProtocol> {
+        let (sender, receiver) = unbounded();
+        self.subscribers
+            .lock()
+            .expect("subscribers lock poisoned")
+            .push(sender);
+        receiver
+    }
+
+    /// Wrap `handler` so every inbound message is content-addressed and
+    /// fanned out to `subscribers`, deduplicated against `seen` only when
+    /// `dedup_gossip` is enabled (duplicate gossip arriving over multiple
+    /// paths is dropped before it reaches anyone; other `Protocol` traffic
+    /// is delivered as-is, since a repeated control frame or direct message
+    /// is often a legitimate resend rather than an echo).
+    fn wrap_handler(
+        handler: NetHandler,
+        subscribers: Arc<Mutex<Vec<Sender<IdentifiedProtocol>>>>,
+        seen: Arc<SeenIdCache>,
+        dedup_gossip: Arc<Mutex<bool>>,
+    ) -> NetHandler {
+        Box::new(move |message| {
+            let message = match message {
+                Ok(protocol) => match ContentId::of(&protocol) {
+                    Ok(id) => {
+                        let dedup = *dedup_gossip.lock().expect("dedup-gossip lock poisoned");
+                        let is_duplicate = dedup && !seen.insert(id.clone());
+                        if is_duplicate {
+                            // Already delivered once; drop without fanning
+                            // out to subscribers or the legacy handler.
+                            return Ok(());
+                        }
+                        fan_out_to_subscribers(
+                            &subscribers,
+                            &IdentifiedProtocol {
+                                protocol: protocol.clone(),
+                                id,
+                            },
+                        );
+                        Ok(protocol)
+                    }
+                    // Couldn't hash it -- fall through to the legacy handler
+                    // without fanning out to subscribers.
+                    Err(_) => Ok(protocol),
+                },
+                Err(e) => Err(e),
+            };
+            handler(message)
+        })
+    }
+
+    /// Drain `outbound_sender`'s receiver on a background thread, forwarding
+    /// each message into the connection thread so subsystems holding only a
+    /// `Sender<Protocol>` clone can drive the connection.
+    ///
+    /// Also listens on a dedicated shutdown channel rather than relying on
+    /// every `Sender<Protocol>` clone being dropped: other subsystems are
+    /// expected to hold onto their clones for as long as they're running, so
+    /// `stop()` needs an explicit way to make this thread exit (and drop its
+    /// `Arc` clone of `connection`) independent of that.
+    fn spawn_outbound_forwarder(
+        connection: Arc<Mutex<NetConnectionThread>>,
+    ) -> (Sender<Protocol>, Sender<()>, thread::JoinHandle<()>) {
+        let (sender, receiver): (Sender<Protocol>, Receiver<Protocol>) = unbounded();
+        let (shutdown_sender, shutdown_receiver) = unbounded::<()>();
+        let handle = thread::spawn(move || loop {
+            crossbeam_channel::select! {
+                recv(receiver) -> msg => match msg {
+                    Ok(protocol) => {
+                        if connection
+                            .lock()
+                            .expect("connection lock poisoned")
+                            .send(protocol)
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                    Err(_) => break,
+                },
+                recv(shutdown_receiver) -> _ => break,
+            }
+        });
+        (sender, shutdown_sender, handle)
     }
 
     /// Stop the network connection (disconnect any sockets, join any threads, etc)
     pub fn stop(self) -> NetResult<()> {
-        self.connection.stop()
+        // The forwarder thread holds its own `Arc` clone of `connection` for
+        // as long as it's running, so it has to be signalled to exit and
+        // joined before `try_unwrap` below can ever succeed -- dropping
+        // `outbound_sender` isn't enough, since other subsystems may still
+        // be holding their own clones of it.
+        let _ = self.forwarder_shutdown.send(());
+        let _ = self.forwarder_handle.join();
+        Arc::try_unwrap(self.connection)
+            .map_err(|_| format_err!("cannot stop P2pNetwork while other handles are live"))?
+            .into_inner()
+            .expect("connection lock poisoned")
+            .stop()
     }
 
     /// Getter of the endpoint of its connection
     pub fn endpoint(&self) -> String {
-        self.connection.endpoint.clone()
+        self.connection
+            .lock()
+            .expect("connection lock poisoned")
+            .endpoint
+            .clone()
     }
 }
 
@@ -65,8 +350,35 @@ impl std::fmt::Debug for P2pNetwork {
 
 impl NetSend for P2pNetwork {
     /// send a Protocol message to the p2p network instance
+    ///
+    /// If a tracer was supplied via `new_with_tracer`, that dispatcher is
+    /// the default on the calling thread while the message is handed to the
+    /// connection thread, so spans and events emitted during that hand-off
+    /// are recorded by it. Spans the caller opened before calling `send()`
+    /// are unaffected, and nothing of the span reaches the connection thread.
+    ///
+    /// Idempotent by content only when constructed via
+    /// `new_with_gossip_dedup`: resending a `Protocol` whose CID we've
+    /// already sent recently is then a no-op. Off by default, since most
+    /// `Protocol` traffic is expected to be legitimately resendable.
     fn send(&mut self, data: Protocol) -> NetResult<()> {
-        self.connection.send(data)
+        let dedup = *self
+            .dedup_gossip
+            .lock()
+            .expect("dedup-gossip lock poisoned");
+        if dedup {
+            if let Ok(id) = ContentId::of(&data) {
+                if !self.sent_ids.insert(id) {
+                    return Ok(());
+                }
+            }
+        }
+        let connection = &self.connection;
+        let send = || connection.lock().expect("connection lock poisoned").send(data);
+        match &self.tracer {
+            Some(tracer) => tracing::dispatcher::with_default(tracer, send),
+            None => send(),
+        }
     }
 }
 
@@ -80,15 +392,61 @@ mod tests {
             P2pBackendKind::IPC,
             crate::ipc_net_worker::IpcNetWorker::ZMQ_URI_CONFIG,
         );
-        let mut res = P2pNetwork::new(Box::new(|_r| Ok(())), &p2p_config).unwrap();
+        let (mut res, _sender) = P2pNetwork::new(Box::new(|_r| Ok(())), &p2p_config).unwrap();
         res.send(Protocol::P2pReady).unwrap();
         res.stop().unwrap();
     }
 
     #[test]
     fn it_should_create_mock() {
-        let mut res = P2pNetwork::new(Box::new(|_r| Ok(())), &P2pConfig::unique_mock()).unwrap();
+        let (mut res, _sender) =
+            P2pNetwork::new(Box::new(|_r| Ok(())), &P2pConfig::unique_mock()).unwrap();
+        res.send(Protocol::P2pReady).unwrap();
+        res.stop().unwrap();
+    }
+
+    #[test]
+    fn it_should_allow_multiple_subscribers() {
+        let (mut res, sender) =
+            P2pNetwork::new(Box::new(|_r| Ok(())), &P2pConfig::unique_mock()).unwrap();
+        let _receiver_a = res.subscribe();
+        let _receiver_b = res.subscribe();
+        // Subsystems can drive the connection through a cloned Sender
+        // without needing their own &mut P2pNetwork.
+        sender.send(Protocol::P2pReady).unwrap();
+        res.send(Protocol::P2pReady).unwrap();
+        res.stop().unwrap();
+    }
+
+    #[test]
+    fn it_should_dedup_identical_sends_when_opted_in() {
+        let (mut res, _sender) =
+            P2pNetwork::new_with_gossip_dedup(Box::new(|_r| Ok(())), &P2pConfig::unique_mock())
+                .unwrap();
+        // First send goes through; the identical resend is dropped as a
+        // duplicate by content id rather than hitting the connection twice.
+        res.send(Protocol::P2pReady).unwrap();
+        res.send(Protocol::P2pReady).unwrap();
+        // The id was recorded by the first send, so a further insert is
+        // reported as a duplicate.
+        assert!(!res
+            .sent_ids
+            .insert(ContentId::of(&Protocol::P2pReady).unwrap()));
+        res.stop().unwrap();
+    }
+
+    #[test]
+    fn it_should_not_dedup_by_default() {
+        let (mut res, _sender) =
+            P2pNetwork::new(Box::new(|_r| Ok(())), &P2pConfig::unique_mock()).unwrap();
+        // Without opting into gossip dedup, resending an identical message
+        // is not silently dropped -- both sends must succeed independently.
+        res.send(Protocol::P2pReady).unwrap();
         res.send(Protocol::P2pReady).unwrap();
+        // Neither send recorded the id, so it is still unseen.
+        assert!(res
+            .sent_ids
+            .insert(ContentId::of(&Protocol::P2pReady).unwrap()));
         res.stop().unwrap();
     }
 }
@@ -0,0 +1,123 @@
+//! Adaptive tick pacing for worker event loops.
+//!
+//! Busy-spinning a worker's `tick()` wastes CPU when idle, and failing
+//! `P2pNetwork::new` outright because a backend isn't ready yet (a slow-to-
+//! start IPC peer, a libp2p listener still binding) is needlessly brittle.
+//! `TickPolicy` tracks the sleep `NetConnectionThread` should take between
+//! ticks, and `StartupRetry` retries connection establishment at a fixed
+//! interval for as long as the backend reports it isn't ready; there is no
+//! cap on the number of attempts.
+
+use std::time::Duration;
+
+/// Sleep thresholds (in microseconds) and the startup-retry interval (in
+/// seconds), read from a backend's `backend_config`. Falls back to
+/// `Default` when the fields are absent from the JSON so existing configs
+/// keep working unchanged.
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+pub struct TickPolicyConfig {
+    #[serde(default = "TickPolicyConfig::default_min_sleep_us")]
+    pub min_sleep_us: u64,
+    #[serde(default = "TickPolicyConfig::default_max_sleep_us")]
+    pub max_sleep_us: u64,
+    #[serde(default = "TickPolicyConfig::default_retry_interval_secs")]
+    pub startup_retry_interval_secs: u64,
+}
+
+impl TickPolicyConfig {
+    fn default_min_sleep_us() -> u64 {
+        100
+    }
+    fn default_max_sleep_us() -> u64 {
+        10_000
+    }
+    fn default_retry_interval_secs() -> u64 {
+        3
+    }
+}
+
+impl Default for TickPolicyConfig {
+    fn default() -> Self {
+        TickPolicyConfig {
+            min_sleep_us: Self::default_min_sleep_us(),
+            max_sleep_us: Self::default_max_sleep_us(),
+            startup_retry_interval_secs: Self::default_retry_interval_secs(),
+        }
+    }
+}
+
+/// Tracks the sleep duration a worker's tick loop should take between
+/// ticks: minimal right after processing a message, exponentially growing
+/// while idle, reset the moment work shows up again.
+pub struct TickPolicy {
+    min_sleep: Duration,
+    max_sleep: Duration,
+    current_sleep: Duration,
+}
+
+impl TickPolicy {
+    pub fn new(config: &TickPolicyConfig) -> Self {
+        let min_sleep = Duration::from_micros(config.min_sleep_us);
+        TickPolicy {
+            min_sleep,
+            max_sleep: Duration::from_micros(config.max_sleep_us),
+            current_sleep: min_sleep,
+        }
+    }
+
+    /// Call after each `tick()`; returns how long to sleep before the next
+    /// one. Doubles the idle sleep up to `max_sleep` when `did_something`
+    /// is false, and resets to `min_sleep` as soon as it's true again.
+    pub fn next_sleep(&mut self, did_something: bool) -> Duration {
+        if did_something {
+            self.current_sleep = self.min_sleep;
+        } else {
+            self.current_sleep = (self.current_sleep * 2).min(self.max_sleep);
+        }
+        self.current_sleep
+    }
+}
+
+impl Default for TickPolicy {
+    fn default() -> Self {
+        TickPolicy::new(&TickPolicyConfig::default())
+    }
+}
+
+/// Retries a fallible connection-establishment closure every
+/// `startup_retry_interval_secs` while it reports the backend isn't ready
+/// yet, rather than failing the whole worker construction.
+pub struct StartupRetry {
+    interval: Duration,
+}
+
+impl StartupRetry {
+    pub fn new(config: &TickPolicyConfig) -> Self {
+        StartupRetry {
+            interval: Duration::from_secs(config.startup_retry_interval_secs),
+        }
+    }
+
+    /// Run `connect` until it succeeds, retrying on `NotReady` errors and
+    /// sleeping `interval` between attempts. Any other error is returned
+    /// immediately.
+    pub fn retry_until_ready<T, E>(
+        &self,
+        mut connect: impl FnMut() -> Result<T, BackendNotReady<E>>,
+    ) -> Result<T, E> {
+        loop {
+            match connect() {
+                Ok(value) => return Ok(value),
+                Err(BackendNotReady::NotReady) => std::thread::sleep(self.interval),
+                Err(BackendNotReady::Other(e)) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Distinguishes "backend isn't up yet, try again" from a real error
+/// during connection establishment.
+pub enum BackendNotReady<E> {
+    NotReady,
+    Other(E),
+}
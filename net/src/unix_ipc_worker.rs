@@ -0,0 +1,212 @@
+//! Unix-domain-socket IPC backend.
+//!
+//! Where `IpcNetWorker` always speaks ZMQ to an out-of-process n3h node,
+//! `UnixIpcWorker` frames `Protocol` messages as length-prefixed bincode
+//! over a tokio `UnixStream`, and can additionally pass open file
+//! descriptors alongside a message via `SCM_RIGHTS` ancillary data. This
+//! suits deployments that co-locate the conductor and its networking
+//! process and want a zero-copy, lower-latency IPC path.
+//!
+//! `Protocol` itself carries no notion of file descriptors, so fd passing
+//! is a side channel next to the regular `NetWorker::receive`/`tick` path:
+//! `send_with_fds` attaches descriptors to an outbound message, and
+//! `take_pending_fds` drains whatever arrived alongside the most recently
+//! read inbound messages.
+
+use failure::format_err;
+use holochain_net_connection::{
+    net_connection::{NetHandler, NetWorker},
+    protocol::Protocol,
+    NetResult,
+};
+
+use crate::tick_policy::{BackendNotReady, StartupRetry, TickPolicy, TickPolicyConfig};
+use std::{
+    collections::VecDeque,
+    io::ErrorKind,
+    os::unix::io::{FromRawFd, RawFd},
+    path::PathBuf,
+};
+use tokio::net::{UnixListener, UnixStream};
+
+mod fd_framing;
+use fd_framing::{read_framed_with_fds, write_framed_with_fds, FramedMessage};
+
+/// JSON shape of `backend_config` for `P2pBackendKind::UnixIpc`.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct UnixIpcConfig {
+    /// Filesystem path of the socket to connect to (or create).
+    #[serde(default)]
+    pub socket_path: Option<PathBuf>,
+    /// When true, bind and listen rather than connect as a client.
+    #[serde(default)]
+    pub listen: bool,
+    /// Idle/backoff sleep thresholds and startup-retry interval.
+    #[serde(flatten, default)]
+    pub tick_policy: TickPolicyConfig,
+}
+
+/// Either an already-established connection, or a bound listener still
+/// waiting for its one peer to show up.
+enum Connection {
+    Listening(UnixListener),
+    Connected(UnixStream),
+}
+
+/// Unix-domain-socket `NetWorker`, optionally carrying file descriptors
+/// alongside each `Protocol` message.
+pub struct UnixIpcWorker {
+    handler: NetHandler,
+    connection: Connection,
+    endpoint: String,
+    inbox: VecDeque<Protocol>,
+    /// Descriptors received alongside the most recently read messages,
+    /// drained by `take_pending_fds`.
+    pending_fds: VecDeque<RawFd>,
+    tick_policy: TickPolicy,
+}
+
+impl UnixIpcWorker {
+    /// Create a worker that creates or connects to the socket described by
+    /// `backend_config`.
+    ///
+    /// In `listen` mode, the socket is bound here but the peer is accepted
+    /// lazily on `tick()` rather than blocking construction indefinitely on
+    /// a client that may not connect for a while. In client mode,
+    /// connecting retries on `ConnectionRefused`/`NotFound` every
+    /// `tick_policy.startup_retry_interval_secs` instead of failing
+    /// outright, tolerating a peer that hasn't bound its socket yet.
+    pub fn new(handler: NetHandler, backend_config: &serde_json::Value) -> NetResult<Self> {
+        let config: UnixIpcConfig = serde_json::from_value(backend_config.clone())?;
+        let path = config
+            .socket_path
+            .clone()
+            .ok_or_else(|| format_err!("UnixIpc backend_config requires `socket_path`"))?;
+
+        let startup_retry = StartupRetry::new(&config.tick_policy);
+        let connection = if config.listen {
+            Connection::Listening(UnixListener::bind(&path)?)
+        } else {
+            let stream = startup_retry.retry_until_ready(|| {
+                futures::executor::block_on(UnixStream::connect(&path)).map_err(|e| {
+                    if e.kind() == ErrorKind::ConnectionRefused || e.kind() == ErrorKind::NotFound
+                    {
+                        BackendNotReady::NotReady
+                    } else {
+                        BackendNotReady::Other(e)
+                    }
+                })
+            })?;
+            Connection::Connected(stream)
+        };
+
+        Ok(UnixIpcWorker {
+            handler,
+            connection,
+            endpoint: format!("unix://{}", path.display()),
+            inbox: VecDeque::new(),
+            pending_fds: VecDeque::new(),
+            tick_policy: TickPolicy::new(&config.tick_policy),
+        })
+    }
+
+    /// Construct a worker from a file descriptor inherited from the parent
+    /// process (systemd-style socket activation), rather than creating or
+    /// connecting the socket ourselves.
+    pub fn from_raw_fd(handler: NetHandler, fd: RawFd) -> NetResult<Self> {
+        let std_stream = unsafe { std::os::unix::net::UnixStream::from_raw_fd(fd) };
+        std_stream.set_nonblocking(true)?;
+        let stream = UnixStream::from_std(std_stream)?;
+        Ok(UnixIpcWorker {
+            handler,
+            connection: Connection::Connected(stream),
+            endpoint: format!("unix://fd/{}", fd),
+            inbox: VecDeque::new(),
+            pending_fds: VecDeque::new(),
+            tick_policy: TickPolicy::default(),
+        })
+    }
+
+    /// Like `receive`, but also passes `fds` alongside the serialized
+    /// message via `SCM_RIGHTS`, e.g. for handing a sharded DB or file
+    /// handle across the IPC boundary.
+    pub fn send_with_fds(&mut self, data: Protocol, fds: &[RawFd]) -> NetResult<()> {
+        match &mut self.connection {
+            Connection::Connected(stream) => {
+                let payload = bincode::serialize(&data)?;
+                write_framed_with_fds(stream, &payload, fds)?;
+                Ok(())
+            }
+            Connection::Listening(_) => Err(format_err!(
+                "UnixIpcWorker cannot send before a peer has connected"
+            )),
+        }
+    }
+
+    /// Drain any file descriptors that arrived alongside the inbound
+    /// messages read so far.
+    pub fn take_pending_fds(&mut self) -> Vec<RawFd> {
+        self.pending_fds.drain(..).collect()
+    }
+
+    /// In listen mode, try (without blocking) to accept the one peer this
+    /// worker expects. No-op once connected.
+    fn try_accept(&mut self) -> NetResult<bool> {
+        if let Connection::Listening(listener) = &mut self.connection {
+            let waker = futures::task::noop_waker();
+            let mut cx = std::task::Context::from_waker(&waker);
+            match listener.poll_accept(&mut cx) {
+                std::task::Poll::Ready(Ok((stream, _addr))) => {
+                    self.connection = Connection::Connected(stream);
+                    return Ok(true);
+                }
+                std::task::Poll::Ready(Err(e)) => return Err(e.into()),
+                std::task::Poll::Pending => {}
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl NetWorker for UnixIpcWorker {
+    fn tick(&mut self) -> NetResult<bool> {
+        let mut did_something = self.try_accept()?;
+
+        let stream = match &mut self.connection {
+            Connection::Connected(stream) => stream,
+            // Still waiting for our peer to connect; nothing to read yet.
+            Connection::Listening(_) => {
+                std::thread::sleep(self.tick_policy.next_sleep(did_something));
+                return Ok(did_something);
+            }
+        };
+
+        while let Some(FramedMessage { payload, fds }) = read_framed_with_fds(stream)? {
+            did_something = true;
+            self.pending_fds.extend(fds);
+            let protocol: Protocol = bincode::deserialize(&payload)?;
+            self.inbox.push_back(protocol);
+        }
+        while let Some(protocol) = self.inbox.pop_front() {
+            did_something = true;
+            (self.handler)(Ok(protocol))?;
+        }
+        std::thread::sleep(self.tick_policy.next_sleep(did_something));
+        Ok(did_something)
+    }
+
+    /// Serialize `data` and send it as one length-prefixed frame with no
+    /// descriptors attached. Use `send_with_fds` directly on the worker
+    /// when descriptors need to ride alongside the message.
+    fn receive(&mut self, data: Protocol) -> NetResult<()> {
+        self.send_with_fds(data, &[])
+    }
+
+    fn stop(self: Box<Self>) -> NetResult<()> {
+        Ok(())
+    }
+
+    fn endpoint(&self) -> Option<String> {
+        Some(self.endpoint.clone())
+    }
+}
@@ -0,0 +1,127 @@
+//! Length-prefixed framing for `UnixIpcWorker`, with file descriptors
+//! carried as `SCM_RIGHTS` ancillary data alongside the length header.
+//!
+//! Frame layout on the wire: a 4-byte big-endian payload length, followed
+//! by the bincode-serialized `Protocol` payload with the same number of
+//! bytes. Any file descriptors associated with the message are not part of
+//! the payload bytes at all -- they ride alongside the header `sendmsg`
+//! call as ancillary data, decoded separately from the payload and handed
+//! back to the caller as a side channel.
+
+use nix::sys::socket::{recvmsg, sendmsg, ControlMessage, ControlMessageOwned, MsgFlags};
+use std::{io, io::IoSlice, io::IoSliceMut, os::unix::io::RawFd};
+use tokio::net::UnixStream;
+
+/// A decoded frame: the raw payload bytes plus any descriptors that were
+/// passed alongside it.
+pub struct FramedMessage {
+    pub payload: Vec<u8>,
+    pub fds: Vec<RawFd>,
+}
+
+/// Read one length-prefixed frame plus any passed descriptors, or `None`
+/// if no byte of a new frame is available yet. Once the first header byte
+/// has been read, this sleeps and retries until the whole frame is in,
+/// rather than returning `None` for a partially arrived frame.
+///
+/// `write_framed_with_fds` attaches `fds` as ancillary data on the
+/// `sendmsg` carrying the length header, not the payload, so fds must be
+/// collected from every `recvmsg` call that can observe them -- including
+/// the header reads, not just the payload loop -- or they're silently
+/// dropped (and leaked) on the receiving end.
+pub fn read_framed_with_fds(stream: &mut UnixStream) -> io::Result<Option<FramedMessage>> {
+    let raw_fd = std::os::unix::io::AsRawFd::as_raw_fd(stream);
+    let mut cmsg_space = nix::cmsg_space!([RawFd; 16]);
+    let mut fds = Vec::new();
+
+    let mut len_buf = [0u8; 4];
+    let mut header_read = 0;
+    // A stream socket can hand back the 4-byte header in more than one
+    // piece just like the payload, so this has to retry on a short read
+    // the same way the payload loop below does.
+    while header_read < len_buf.len() {
+        let iov = [IoSliceMut::new(&mut len_buf[header_read..])];
+        match recvmsg(raw_fd, &iov, Some(&mut cmsg_space), MsgFlags::MSG_DONTWAIT) {
+            Ok(msg) if msg.bytes == 0 => {
+                if header_read == 0 {
+                    return Ok(None);
+                }
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "peer closed mid-frame",
+                ));
+            }
+            Ok(msg) => {
+                header_read += msg.bytes;
+                fds.extend(msg.cmsgs().filter_map(|cmsg| match cmsg {
+                    ControlMessageOwned::ScmRights(fds) => Some(fds),
+                    _ => None,
+                }).flatten());
+            }
+            Err(nix::errno::Errno::EAGAIN) => {
+                if header_read == 0 {
+                    return Ok(None);
+                }
+                std::thread::sleep(std::time::Duration::from_micros(50));
+                continue;
+            }
+            Err(e) => return Err(io::Error::from(e)),
+        }
+    }
+
+    let len = u32::from_be_bytes(len_buf) as usize;
+    let mut payload = vec![0u8; len];
+    let mut read = 0;
+    // The length header having arrived doesn't guarantee the payload has
+    // too -- a short read is normal for a stream socket, and the rest may
+    // not have landed yet on a non-blocking fd, so keep retrying until all
+    // `len` bytes are in rather than trusting a single recvmsg call.
+    while read < len {
+        let iov = [IoSliceMut::new(&mut payload[read..])];
+        match recvmsg(raw_fd, &iov, Some(&mut cmsg_space), MsgFlags::MSG_DONTWAIT) {
+            Ok(msg) if msg.bytes == 0 => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "peer closed mid-frame",
+                ))
+            }
+            Ok(msg) => {
+                read += msg.bytes;
+                fds.extend(msg.cmsgs().filter_map(|cmsg| match cmsg {
+                    ControlMessageOwned::ScmRights(fds) => Some(fds),
+                    _ => None,
+                }).flatten());
+            }
+            Err(nix::errno::Errno::EAGAIN) => {
+                std::thread::sleep(std::time::Duration::from_micros(50));
+                continue;
+            }
+            Err(e) => return Err(io::Error::from(e)),
+        }
+    }
+
+    Ok(Some(FramedMessage { payload, fds }))
+}
+
+/// Write one length-prefixed frame, passing `fds` alongside it as
+/// `SCM_RIGHTS` ancillary data.
+pub fn write_framed_with_fds(
+    stream: &mut UnixStream,
+    payload: &[u8],
+    fds: &[RawFd],
+) -> io::Result<()> {
+    let raw_fd = std::os::unix::io::AsRawFd::as_raw_fd(stream);
+    let len = (payload.len() as u32).to_be_bytes();
+
+    let iov = [IoSlice::new(&len)];
+    let cmsgs = if fds.is_empty() {
+        vec![]
+    } else {
+        vec![ControlMessage::ScmRights(fds)]
+    };
+    sendmsg(raw_fd, &iov, &cmsgs, MsgFlags::empty(), None)?;
+
+    let iov = [IoSlice::new(payload)];
+    sendmsg(raw_fd, &iov, &[], MsgFlags::empty(), None)?;
+    Ok(())
+}